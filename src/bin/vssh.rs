@@ -1,24 +1,107 @@
 use anyhow::{anyhow, Context, Result};
 use nix::fcntl::{open, OFlag};
+use nix::sys::signal::{kill, Signal};
 use nix::sys::stat::Mode;
-use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{dup2, execvp, fork, pipe, ForkResult, Pid};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{dup2, execvp, fork, pipe, setpgid, ForkResult, Pid};
+use std::collections::HashMap;
 use std::env;
 use std::ffi::CString;
-use std::io::{self, Write};
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
 use std::os::unix::io::{AsRawFd, OwnedFd};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
 
 #[derive(Clone)]
 struct ParsedLine {
     background: bool,
-    input: Option<String>,
-    output: Option<String>,
-    pipeline: Vec<Vec<CString>>,
+    pipeline: Vec<PipelineStage>,
+    raw: String,
+}
+
+#[derive(Clone)]
+struct PipelineStage {
+    argv: Vec<CString>,
+    redirs: Vec<Redirection>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RedirMode {
+    Read,
+    Truncate,
+    Append,
+    Dup,
+}
+
+#[derive(Clone)]
+enum RedirTarget {
+    File(String),
+    Fd(i32),
+}
+
+#[derive(Clone)]
+struct Redirection {
+    fd: i32,
+    target: RedirTarget,
+    mode: RedirMode,
+}
+
+// A word as produced by the lexer: the segments drawn from single quotes
+// are kept literal, unquoted and double-quoted segments are still eligible
+// for variable expansion, and `$(cmd)`/backtick segments hold a command to
+// run and capture later. `quoted` records whether any quoting touched this
+// word at all, since that also suppresses tilde, glob, and word-split
+// expansion for the word as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Word {
+    segments: Vec<WordSegment>,
+    quoted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WordSegment {
+    Literal(String),
+    Plain(String),
+    Substitution(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(Word),
+    Pipe,
+    RedirIn,
+    RedirOut(i32),
+    RedirAppend(i32),
+    FdDup(i32, i32),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JobStatus {
+    Running,
+    Stopped,
+}
+
+struct Job {
+    id: usize,
+    pgid: Pid,
+    pids: Vec<Pid>,
+    command: String,
+    status: JobStatus,
+}
+
+// Matches `name` as the whole first word of `line`, not just a prefix of it,
+// so a command like `fgrep` isn't mistaken for the `fg` builtin. Returns the
+// (untrimmed) remainder of the line when it matches.
+fn match_builtin<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.strip_prefix(name).filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
 }
 
 fn main() -> Result<()> {
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut next_job_id: usize = 1;
     loop {
+        reap_finished_jobs(&mut jobs);
         let cwd = env::current_dir().map(|p| p.display().to_string()).unwrap_or_else(|_| "?".to_string());
         print!("{}$ ", cwd);
         io::stdout().flush().ok();
@@ -27,54 +110,367 @@ fn main() -> Result<()> {
         let line = line.trim().to_string();
         if line.is_empty() { continue; }
         if line == "exit" { break; }
-        if line.starts_with("cd") { if let Err(e) = builtin_cd(&line) { eprintln!("{e}"); } continue; }
+        if match_builtin(&line, "cd").is_some() { if let Err(e) = builtin_cd(&line) { eprintln!("{e}"); } continue; }
+        if line == "jobs" { builtin_jobs(&jobs); continue; }
+        if let Some(rest) = match_builtin(&line, "fg") {
+            if let Err(e) = builtin_fg(rest.trim(), &mut jobs) { eprintln!("{e}"); }
+            continue;
+        }
+        if let Some(rest) = match_builtin(&line, "bg") {
+            if let Err(e) = builtin_bg(rest.trim(), &mut jobs) { eprintln!("{e}"); }
+            continue;
+        }
+        if let Some(rest) = match_builtin(&line, "parallel") {
+            if let Err(e) = builtin_parallel(rest.trim()) { eprintln!("{e}"); }
+            continue;
+        }
+        if let Some(rest) = match_builtin(&line, "mmv") {
+            if let Err(e) = builtin_mmv(rest.trim()) { eprintln!("{e}"); }
+            continue;
+        }
         let parsed = match parse_line(&line) { Ok(p) => p, Err(e) => { eprintln!("parse error: {e}"); continue; } };
-        if let Err(e) = execute(parsed) { eprintln!("error: {e}"); }
+        if let Err(e) = execute(parsed, &mut jobs, &mut next_job_id) { eprintln!("error: {e}"); }
     }
     Ok(())
 }
 
 fn builtin_cd(line: &str) -> Result<()> {
-    let parts = shell_split(line);
-    if parts.len() == 1 {
+    let rest = line.strip_prefix("cd").unwrap_or(line).trim();
+    if rest.is_empty() {
         let home = env::var("HOME").map_err(|_| anyhow!("HOME not set"))?;
         env::set_current_dir(Path::new(&home)).with_context(|| "failed to change directory to HOME")?;
     } else {
-        let target = &parts[1];
-        env::set_current_dir(Path::new(target)).with_context(|| format!("cd: no such file or directory: {target}"))?;
+        let mut args: Vec<String> = Vec::new();
+        for tok in tokenize(rest)? {
+            if let Token::Word(w) = tok { args.extend(expand_word(&w)); }
+        }
+        let target = args.into_iter().next().ok_or_else(|| anyhow!("cd: missing argument"))?;
+        env::set_current_dir(Path::new(&target)).with_context(|| format!("cd: no such file or directory: {target}"))?;
+    }
+    Ok(())
+}
+
+fn builtin_jobs(jobs: &[Job]) {
+    for job in jobs {
+        let status = match job.status { JobStatus::Running => "Running", JobStatus::Stopped => "Stopped" };
+        println!("[{}]  {}\t{}", job.id, status, job.command);
+    }
+}
+
+fn parse_job_spec(spec: &str) -> Option<usize> {
+    let spec = spec.strip_prefix('%').unwrap_or(spec);
+    spec.parse::<usize>().ok()
+}
+
+fn builtin_fg(spec: &str, jobs: &mut Vec<Job>) -> Result<()> {
+    let id = parse_job_spec(spec).ok_or_else(|| anyhow!("fg: usage: fg %<job-id>"))?;
+    let idx = jobs.iter().position(|j| j.id == id).ok_or_else(|| anyhow!("fg: no such job: {id}"))?;
+    println!("{}", jobs[idx].command);
+    if jobs[idx].status == JobStatus::Stopped {
+        kill(Pid::from_raw(-jobs[idx].pgid.as_raw()), Signal::SIGCONT)?;
+        jobs[idx].status = JobStatus::Running;
+    }
+    let pids = jobs[idx].pids.clone();
+    let mut stopped = false;
+    let mut remaining = Vec::new();
+    for pid in pids {
+        match waitpid(pid, Some(WaitPidFlag::WUNTRACED)) {
+            Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => {}
+            Ok(WaitStatus::Stopped(_, _)) => { stopped = true; remaining.push(pid); }
+            Ok(_) => remaining.push(pid),
+            Err(_) => {}
+        }
+    }
+    if stopped {
+        jobs[idx].pids = remaining;
+        jobs[idx].status = JobStatus::Stopped;
+        println!("[{}]+ Stopped\t{}", jobs[idx].id, jobs[idx].command);
+    } else {
+        jobs.remove(idx);
+    }
+    Ok(())
+}
+
+fn builtin_bg(spec: &str, jobs: &mut [Job]) -> Result<()> {
+    let id = parse_job_spec(spec).ok_or_else(|| anyhow!("bg: usage: bg %<job-id>"))?;
+    let job = jobs.iter_mut().find(|j| j.id == id).ok_or_else(|| anyhow!("bg: no such job: {id}"))?;
+    kill(Pid::from_raw(-job.pgid.as_raw()), Signal::SIGCONT)?;
+    job.status = JobStatus::Running;
+    println!("[{}]+ {} &", job.id, job.command);
+    Ok(())
+}
+
+// `mmv [-n] [-b] [-0] path...` lets the user rename a batch of files by
+// editing a one-path-per-line list in $EDITOR, like the classic `mmv` tool.
+fn builtin_mmv(rest: &str) -> Result<()> {
+    let mut dry_run = false;
+    let mut backup = false;
+    let mut nul_separated = false;
+    let mut paths: Vec<String> = Vec::new();
+    for tok in shell_split(rest) {
+        match tok.as_str() {
+            "-n" => dry_run = true,
+            "-b" => backup = true,
+            "-0" => nul_separated = true,
+            _ => paths.extend(expand_glob(&tok)),
+        }
+    }
+    if paths.is_empty() { return Err(anyhow!("mmv: no files given")); }
+
+    let sep = if nul_separated { '\0' } else { '\n' };
+    let mut listing = String::new();
+    for p in &paths { listing.push_str(p); listing.push(sep); }
+
+    let mut tmp = NamedTempFile::new().with_context(|| "mmv: failed to create temp file")?;
+    tmp.write_all(listing.as_bytes())?;
+    tmp.flush()?;
+
+    let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let mut editor_argv = shell_split(&editor);
+    editor_argv.push(tmp.path().display().to_string());
+    run_foreground(&editor_argv)?;
+
+    let mut edited = String::new();
+    fs::File::open(tmp.path())?.read_to_string(&mut edited)?;
+    let new_names: Vec<String> = edited.split(sep).map(|s| s.trim_end_matches('\r').to_string()).filter(|s| !s.is_empty()).collect();
+    if new_names.len() != paths.len() {
+        return Err(anyhow!("mmv: files added or removed during editing"));
+    }
+
+    let mut dest_counts: HashMap<&str, usize> = HashMap::new();
+    for name in &new_names { *dest_counts.entry(name.as_str()).or_insert(0) += 1; }
+    if let Some((dup, _)) = dest_counts.iter().find(|&(_, &count)| count > 1) {
+        return Err(anyhow!("mmv: duplicate destination: {dup}"));
+    }
+
+    let moves: Vec<(String, String)> = paths.into_iter().zip(new_names).filter(|(old, new)| old != new).collect();
+    if dry_run {
+        for (old, new) in &moves { println!("{old} -> {new}"); }
+        return Ok(());
+    }
+    perform_renames(moves, backup)
+}
+
+// Renames can form chains (a->b, b->c) or cycles (a->b, b->a). A move whose
+// destination is not the source of another pending move is done first, so a
+// chain is resolved by ordering alone; when only cycles remain, one source is
+// moved to a unique temporary name first, breaking the cycle instead of
+// clobbering a file that is still to be moved.
+fn perform_renames(mut pending: Vec<(String, String)>, backup: bool) -> Result<()> {
+    let mut tmp_counter = 0u32;
+    while !pending.is_empty() {
+        let safe_idx = pending.iter().position(|(_, dst)| !pending.iter().any(|(src, _)| src == dst));
+        if let Some(i) = safe_idx {
+            let (src, dst) = pending.remove(i);
+            rename_one(&src, &dst, backup)?;
+        } else {
+            let (src, dst) = pending[0].clone();
+            let tmp_name = unique_temp_name(&src, tmp_counter)?;
+            tmp_counter += 1;
+            fs::rename(&src, &tmp_name).with_context(|| format!("mmv: failed to rename {src} to {tmp_name}"))?;
+            pending[0] = (tmp_name, dst);
+        }
     }
     Ok(())
 }
 
-fn execute(pl: ParsedLine) -> Result<()> {
-    if pl.pipeline.len() == 1 { return exec_single(pl); }
-    exec_pipeline(pl)
+fn rename_one(src: &str, dst: &str, backup: bool) -> Result<()> {
+    if backup && fs::symlink_metadata(dst).is_ok() {
+        let backup_name = format!("{dst}~");
+        fs::rename(dst, &backup_name).with_context(|| format!("mmv: failed to back up {dst} to {backup_name}"))?;
+    }
+    fs::rename(src, dst).with_context(|| format!("mmv: failed to rename {src} to {dst}"))
+}
+
+fn unique_temp_name(src: &str, counter: u32) -> Result<String> {
+    let path = Path::new(src);
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let file_name = path.file_name().ok_or_else(|| anyhow!("mmv: invalid path: {src}"))?.to_string_lossy();
+    let candidate: PathBuf = dir.join(format!(".mmv-tmp.{}.{}.{}", std::process::id(), counter, file_name));
+    Ok(candidate.display().to_string())
 }
 
-fn exec_single(pl: ParsedLine) -> Result<()> {
-    let argv = &pl.pipeline[0];
+// Forks, execs argv[0] with the given arguments, and blocks until it exits.
+fn run_foreground(argv: &[String]) -> Result<()> {
+    let argv: Vec<CString> = argv.iter().map(|s| CString::new(s.as_str()).map_err(|_| anyhow!("NUL in arg"))).collect::<Result<Vec<_>>>()?;
     match unsafe { fork()? } {
         ForkResult::Child => {
-            if let Some(ref infile) = pl.input {
-                let fd = open(Path::new(infile), OFlag::O_RDONLY, Mode::from_bits_truncate(0o644)).with_context(|| format!("cannot open for input: {infile}"))?;
-                dup2(fd, 0).ok();
+            let err = execvp(&argv[0], &argv).err().unwrap();
+            eprintln!("exec failed: {err}");
+            std::process::exit(127);
+        }
+        ForkResult::Parent { child } => loop {
+            match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => return Ok(()),
+                Ok(_) => continue,
+                Err(e) => return Err(anyhow!("waitpid failed: {e}")),
             }
-            if let Some(ref outfile) = pl.output {
-                let fd = open(Path::new(outfile), OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC, Mode::from_bits_truncate(0o644)).with_context(|| format!("cannot open for output: {outfile}"))?;
-                dup2(fd, 1).ok();
+        },
+    }
+}
+
+// `parallel [-j N] CMD {} ::: arg1 arg2 ...` substitutes `{}` in the command
+// template for each input and runs up to N children at a time, reusing the
+// fork/execvp/waitpid machinery of exec_single but as a throttled batch pool.
+fn builtin_parallel(rest: &str) -> Result<()> {
+    let tokens = shell_split(rest);
+    let mut idx = 0;
+    let mut pool_size = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    if tokens.first().map(|s| s.as_str()) == Some("-j") {
+        let n = tokens.get(1).ok_or_else(|| anyhow!("parallel: -j requires a number"))?;
+        pool_size = n.parse().with_context(|| format!("parallel: invalid -j value: {n}"))?;
+        if pool_size == 0 { return Err(anyhow!("parallel: -j requires a positive integer")); }
+        idx = 2;
+    }
+
+    let mut template: Vec<String> = Vec::new();
+    while idx < tokens.len() && tokens[idx] != ":::" {
+        template.push(tokens[idx].clone());
+        idx += 1;
+    }
+    if template.is_empty() { return Err(anyhow!("parallel: missing command")); }
+
+    let inputs: Vec<String> = if idx < tokens.len() {
+        tokens[idx + 1..].to_vec()
+    } else {
+        io::stdin().lock().lines().collect::<io::Result<Vec<_>>>()?
+    };
+
+    let mut running: Vec<Pid> = Vec::new();
+    let mut any_failed = false;
+    for input in &inputs {
+        if running.len() >= pool_size {
+            reap_one_parallel_job(&mut running, &mut any_failed)?;
+        }
+        let argv: Vec<CString> = template
+            .iter()
+            .map(|t| if t == "{}" { input.clone() } else { t.clone() })
+            .map(|s| CString::new(s).map_err(|_| anyhow!("NUL in arg")))
+            .collect::<Result<Vec<_>>>()?;
+        match unsafe { fork()? } {
+            ForkResult::Child => {
+                let err = execvp(&argv[0], &argv).err().unwrap();
+                eprintln!("exec failed: {err}");
+                std::process::exit(127);
             }
-            let err = execvp(&argv[0], &argv).err().unwrap();
+            ForkResult::Parent { child } => running.push(child),
+        }
+    }
+    while !running.is_empty() {
+        reap_one_parallel_job(&mut running, &mut any_failed)?;
+    }
+
+    if any_failed { return Err(anyhow!("parallel: one or more jobs failed")); }
+    Ok(())
+}
+
+// Waits only on pids from the pool, never `-1`, so a background job from the
+// main job table that happens to exit while `parallel` is running is left
+// alone for `reap_finished_jobs` to reap instead of being stolen here.
+fn reap_one_parallel_job(running: &mut Vec<Pid>, any_failed: &mut bool) -> Result<()> {
+    loop {
+        for &pid in running.iter() {
+            match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(pid, code)) => {
+                    running.retain(|&p| p != pid);
+                    if code != 0 { *any_failed = true; }
+                    return Ok(());
+                }
+                Ok(WaitStatus::Signaled(pid, _, _)) => {
+                    running.retain(|&p| p != pid);
+                    *any_failed = true;
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(e) => return Err(anyhow!("waitpid failed: {e}")),
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+fn reap_finished_jobs(jobs: &mut Vec<Job>) {
+    loop {
+        match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED)) {
+            Ok(WaitStatus::Exited(pid, _)) | Ok(WaitStatus::Signaled(pid, _, _)) => {
+                if let Some(idx) = jobs.iter().position(|j| j.pids.contains(&pid)) {
+                    jobs[idx].pids.retain(|&p| p != pid);
+                    if jobs[idx].pids.is_empty() {
+                        println!("[{}]+ Done\t{}", jobs[idx].id, jobs[idx].command);
+                        jobs.remove(idx);
+                    }
+                }
+            }
+            Ok(WaitStatus::Stopped(pid, _)) => {
+                if let Some(job) = jobs.iter_mut().find(|j| j.pids.contains(&pid)) {
+                    job.status = JobStatus::Stopped;
+                }
+            }
+            Ok(WaitStatus::StillAlive) => break,
+            Err(_) => break,
+            _ => continue,
+        }
+    }
+}
+
+fn execute(pl: ParsedLine, jobs: &mut Vec<Job>, next_job_id: &mut usize) -> Result<()> {
+    if pl.pipeline.len() == 1 { return exec_single(pl, jobs, next_job_id); }
+    exec_pipeline(pl, jobs, next_job_id)
+}
+
+fn apply_redirections(redirs: &[Redirection]) -> Result<()> {
+    for r in redirs {
+        match &r.target {
+            RedirTarget::File(path) => {
+                let flags = match r.mode {
+                    RedirMode::Read => OFlag::O_RDONLY,
+                    RedirMode::Truncate => OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC,
+                    RedirMode::Append => OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_APPEND,
+                    RedirMode::Dup => unreachable!("dup redirections never target a file"),
+                };
+                let fd = open(Path::new(path), flags, Mode::from_bits_truncate(0o644)).with_context(|| format!("cannot open for redirection: {path}"))?;
+                dup2(fd, r.fd).ok();
+            }
+            RedirTarget::Fd(target_fd) => {
+                dup2(*target_fd, r.fd).ok();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn exec_single(pl: ParsedLine, jobs: &mut Vec<Job>, next_job_id: &mut usize) -> Result<()> {
+    let argv = &pl.pipeline[0].argv;
+    match unsafe { fork()? } {
+        ForkResult::Child => {
+            setpgid(Pid::from_raw(0), Pid::from_raw(0)).ok();
+            if let Err(e) = apply_redirections(&pl.pipeline[0].redirs) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            let err = execvp(&argv[0], argv).err().unwrap();
             eprintln!("exec failed: {err}");
             std::process::exit(127);
         }
         ForkResult::Parent { child } => {
+            setpgid(child, child).ok();
             if pl.background {
-                println!("Starting background process {}", child.as_raw());
+                let id = *next_job_id;
+                *next_job_id += 1;
+                println!("[{id}] {}", child.as_raw());
+                jobs.push(Job { id, pgid: child, pids: vec![child], command: pl.raw.clone(), status: JobStatus::Running });
                 return Ok(());
             } else {
                 loop {
-                    match waitpid(child, None) {
+                    match waitpid(child, Some(WaitPidFlag::WUNTRACED)) {
                         Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => break,
+                        Ok(WaitStatus::Stopped(_, _)) => {
+                            let id = *next_job_id;
+                            *next_job_id += 1;
+                            println!("[{id}]+ Stopped\t{}", pl.raw);
+                            jobs.push(Job { id, pgid: child, pids: vec![child], command: pl.raw.clone(), status: JobStatus::Stopped });
+                            break;
+                        }
                         Ok(_) => continue,
                         Err(e) => return Err(anyhow!("waitpid failed: {e}")),
                     }
@@ -85,10 +481,11 @@ fn exec_single(pl: ParsedLine) -> Result<()> {
     Ok(())
 }
 
-fn exec_pipeline(pl: ParsedLine) -> Result<()> {
+fn exec_pipeline(pl: ParsedLine, jobs: &mut Vec<Job>, next_job_id: &mut usize) -> Result<()> {
     let n = pl.pipeline.len();
     let mut pids: Vec<Pid> = Vec::with_capacity(n);
     let mut prev_read_end: Option<OwnedFd> = None;
+    let mut pgid: Option<Pid> = None;
 
     for i in 0..n {
         let (read_end, write_end): (Option<OwnedFd>, Option<OwnedFd>) =
@@ -96,28 +493,24 @@ fn exec_pipeline(pl: ParsedLine) -> Result<()> {
 
         match unsafe { fork()? } {
             ForkResult::Child => {
-                if i == 0 {
-                    if let Some(ref infile) = pl.input {
-                        let fd = open(Path::new(infile), OFlag::O_RDONLY, Mode::from_bits_truncate(0o644)).with_context(|| format!("cannot open for input: {infile}"))?;
-                        dup2(fd, 0).ok();
-                    }
-                }
+                setpgid(Pid::from_raw(0), pgid.unwrap_or(Pid::from_raw(0))).ok();
                 if let Some(ref prev_r) = prev_read_end { dup2(prev_r.as_raw_fd(), 0).ok(); }
-                if let Some(ref w) = write_end {
-                    dup2(w.as_raw_fd(), 1).ok();
-                } else if let Some(ref outfile) = pl.output {
-                    let fd = open(Path::new(outfile), OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC, Mode::from_bits_truncate(0o644)).with_context(|| format!("cannot open for output: {outfile}"))?;
-                    dup2(fd, 1).ok();
+                if let Some(ref w) = write_end { dup2(w.as_raw_fd(), 1).ok(); }
+                if let Err(e) = apply_redirections(&pl.pipeline[i].redirs) {
+                    eprintln!("{e}");
+                    std::process::exit(1);
                 }
                 drop(prev_read_end);
                 drop(read_end);
                 drop(write_end);
-                let argv = &pl.pipeline[i];
-                let err = execvp(&argv[0], &argv).err().unwrap();
+                let argv = &pl.pipeline[i].argv;
+                let err = execvp(&argv[0], argv).err().unwrap();
                 eprintln!("exec failed: {err}");
                 std::process::exit(127);
             }
             ForkResult::Parent { child } => {
+                if pgid.is_none() { pgid = Some(child); }
+                setpgid(child, pgid.unwrap()).ok();
                 pids.push(child);
                 drop(prev_read_end);
                 if let Some(w) = write_end { drop(w); }
@@ -125,68 +518,493 @@ fn exec_pipeline(pl: ParsedLine) -> Result<()> {
             }
         }
     }
+    let pgid = pgid.unwrap();
 
     if pl.background {
-        if let Some(first) = pids.first() { println!("Starting background process {}", first.as_raw()); }
+        let id = *next_job_id;
+        *next_job_id += 1;
+        println!("[{id}] {}", pgid.as_raw());
+        jobs.push(Job { id, pgid, pids, command: pl.raw.clone(), status: JobStatus::Running });
         return Ok(());
     }
 
+    let mut stopped_pids = Vec::new();
     for pid in pids {
         loop {
-            match waitpid(pid, None) {
+            match waitpid(pid, Some(WaitPidFlag::WUNTRACED)) {
                 Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => break,
+                Ok(WaitStatus::Stopped(_, _)) => { stopped_pids.push(pid); break; }
                 Ok(_) => continue,
                 Err(e) => return Err(anyhow!("waitpid failed: {e}")),
             }
         }
     }
+    if !stopped_pids.is_empty() {
+        let id = *next_job_id;
+        *next_job_id += 1;
+        println!("[{id}]+ Stopped\t{}", pl.raw);
+        jobs.push(Job { id, pgid, pids: stopped_pids, command: pl.raw.clone(), status: JobStatus::Stopped });
+    }
     Ok(())
 }
 
 fn parse_line(line: &str) -> Result<ParsedLine> {
-    let mut s = line.trim().to_string();
+    let raw = line.trim().to_string();
+    let mut s = raw.clone();
     let mut background = false;
     if s.ends_with('&') { background = true; s.pop(); s = s.trim_end().to_string(); }
-    let mut segments: Vec<String> = s.split('|').map(|t| t.trim().to_string()).collect();
-    if segments.is_empty() { return Err(anyhow!("empty command")); }
-    let mut input: Option<String> = None;
-    let mut output: Option<String> = None;
-
-    {
-        let seg = &segments[0];
-        if seg.contains('<') {
-            let parts: Vec<&str> = seg.split('<').collect();
-            if parts.len() != 2 { return Err(anyhow!("invalid input redirection")); }
-            let cmd = parts[0].trim();
-            let file = parts[1].trim();
-            if file.is_empty() { return Err(anyhow!("missing input filename")); }
-            input = Some(file.to_string());
-            segments[0] = cmd.to_string();
-        }
-    }
-    let last = segments.len() - 1;
-    {
-        let seg = &segments[last];
-        if seg.contains('>') {
-            let parts: Vec<&str> = seg.split('>').collect();
-            if parts.len() != 2 { return Err(anyhow!("invalid output redirection")); }
-            let cmd = parts[0].trim();
-            let file = parts[1].trim();
-            if file.is_empty() { return Err(anyhow!("missing output filename")); }
-            output = Some(file.to_string());
-            segments[last] = cmd.to_string();
-        }
-    }
-
-    let mut pipeline: Vec<Vec<CString>> = Vec::new();
-    for seg in segments {
-        let tokens = shell_split(&seg);
-        if tokens.is_empty() { return Err(anyhow!("empty pipeline segment")); }
-        let argv: Vec<CString> = tokens.into_iter().map(|t| CString::new(t).map_err(|_| anyhow!("NUL in arg"))).collect::<Result<Vec<_>>>()?;
-        pipeline.push(argv);
-    }
-
-    Ok(ParsedLine { background, input, output, pipeline })
+    let tokens = tokenize(&s)?;
+    let pipeline = build_pipeline(tokens)?;
+    Ok(ParsedLine { background, pipeline, raw })
+}
+
+// Scans the line once, honoring the quote rules from `shell_split`, and emits
+// structured tokens so redirections and pipes can be recognized even when
+// similar characters appear inside quotes. Each word keeps track of which
+// parts came from single quotes so a later expansion pass can leave them
+// untouched.
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = s.chars().collect();
+    let n = chars.len();
+    let mut i = 0;
+    let mut tokens: Vec<Token> = Vec::new();
+
+    let mut segments: Vec<WordSegment> = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut word_active = false;
+    let mut in_quotes = false;
+    let mut quote_char = ' ';
+
+    while i < n {
+        let c = chars[i];
+        if in_quotes {
+            if c == quote_char {
+                in_quotes = false;
+                if quote_char == '\'' {
+                    segments.push(WordSegment::Literal(std::mem::take(&mut cur)));
+                } else {
+                    flush_plain(&mut cur, &mut segments);
+                }
+                i += 1;
+            } else if quote_char == '"' && c == '$' && i + 1 < n && chars[i + 1] == '(' {
+                flush_plain(&mut cur, &mut segments);
+                let (inner, next_i) = scan_paren_substitution(&chars, i + 2)?;
+                segments.push(WordSegment::Substitution(inner));
+                i = next_i;
+            } else if quote_char == '"' && c == '`' {
+                flush_plain(&mut cur, &mut segments);
+                let (inner, next_i) = scan_backtick_substitution(&chars, i + 1)?;
+                segments.push(WordSegment::Substitution(inner));
+                i = next_i;
+            } else {
+                cur.push(c);
+                i += 1;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => {
+                flush_plain(&mut cur, &mut segments);
+                in_quotes = true;
+                quote_char = c;
+                quoted = true;
+                word_active = true;
+                i += 1;
+            }
+            '$' if i + 1 < n && chars[i + 1] == '(' => {
+                flush_plain(&mut cur, &mut segments);
+                let (inner, next_i) = scan_paren_substitution(&chars, i + 2)?;
+                segments.push(WordSegment::Substitution(inner));
+                word_active = true;
+                i = next_i;
+            }
+            '`' => {
+                flush_plain(&mut cur, &mut segments);
+                let (inner, next_i) = scan_backtick_substitution(&chars, i + 1)?;
+                segments.push(WordSegment::Substitution(inner));
+                word_active = true;
+                i = next_i;
+            }
+            ' ' | '\t' => {
+                flush_word(&mut cur, &mut segments, &mut quoted, &mut word_active, &mut tokens);
+                i += 1;
+            }
+            '|' => {
+                flush_word(&mut cur, &mut segments, &mut quoted, &mut word_active, &mut tokens);
+                tokens.push(Token::Pipe);
+                i += 1;
+            }
+            '<' => {
+                let fd = take_fd_prefix(&mut cur, &mut segments, &mut quoted, &mut word_active, &mut tokens, 0);
+                i += 1;
+                if i < n && chars[i] == '&' {
+                    i += 1;
+                    let target_fd = take_fd_target(&chars, &mut i)?;
+                    tokens.push(Token::FdDup(fd, target_fd));
+                } else {
+                    tokens.push(Token::RedirIn);
+                }
+            }
+            '>' => {
+                let fd = take_fd_prefix(&mut cur, &mut segments, &mut quoted, &mut word_active, &mut tokens, 1);
+                i += 1;
+                if i < n && chars[i] == '>' {
+                    i += 1;
+                    tokens.push(Token::RedirAppend(fd));
+                } else if i < n && chars[i] == '&' {
+                    i += 1;
+                    let target_fd = take_fd_target(&chars, &mut i)?;
+                    tokens.push(Token::FdDup(fd, target_fd));
+                } else {
+                    tokens.push(Token::RedirOut(fd));
+                }
+            }
+            _ => { cur.push(c); word_active = true; i += 1; }
+        }
+    }
+    flush_word(&mut cur, &mut segments, &mut quoted, &mut word_active, &mut tokens);
+    if in_quotes { return Err(anyhow!("unterminated quote")); }
+    Ok(tokens)
+}
+
+fn flush_plain(cur: &mut String, segments: &mut Vec<WordSegment>) {
+    if !cur.is_empty() { segments.push(WordSegment::Plain(std::mem::take(cur))); }
+}
+
+fn flush_word(cur: &mut String, segments: &mut Vec<WordSegment>, quoted: &mut bool, word_active: &mut bool, tokens: &mut Vec<Token>) {
+    flush_plain(cur, segments);
+    if *word_active {
+        tokens.push(Token::Word(Word { segments: std::mem::take(segments), quoted: *quoted }));
+    }
+    *word_active = false;
+    *quoted = false;
+}
+
+// If `cur` holds only digits with no quoting yet (e.g. the "2" in "2>err"),
+// consumes it as an explicit source fd; otherwise flushes the pending word
+// as a token and returns `default_fd`.
+fn take_fd_prefix(cur: &mut String, segments: &mut Vec<WordSegment>, quoted: &mut bool, word_active: &mut bool, tokens: &mut Vec<Token>, default_fd: i32) -> i32 {
+    if segments.is_empty() && !*quoted && !cur.is_empty() && cur.chars().all(|c| c.is_ascii_digit()) {
+        let fd = cur.parse().unwrap_or(default_fd);
+        cur.clear();
+        *word_active = false;
+        fd
+    } else {
+        flush_word(cur, segments, quoted, word_active, tokens);
+        default_fd
+    }
+}
+
+fn take_fd_target(chars: &[char], i: &mut usize) -> Result<i32> {
+    let mut digits = String::new();
+    while *i < chars.len() && chars[*i].is_ascii_digit() { digits.push(chars[*i]); *i += 1; }
+    digits.parse().map_err(|_| anyhow!("invalid fd in redirection"))
+}
+
+// Reads the body of a `$(...)` substitution starting just past the opening
+// paren, tracking nested parens and skipping over quoted spans so a `)`
+// inside a quoted argument doesn't close the substitution early. Returns the
+// inner command text and the index just past the closing paren.
+fn scan_paren_substitution(chars: &[char], start: usize) -> Result<(String, usize)> {
+    let mut depth = 1;
+    let mut i = start;
+    let mut in_single = false;
+    let mut in_double = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if in_single {
+            if c == '\'' { in_single = false; }
+        } else if in_double {
+            if c == '"' { in_double = false; }
+        } else {
+            match c {
+                '\'' => in_single = true,
+                '"' => in_double = true,
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok((chars[start..i].iter().collect(), i + 1));
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    Err(anyhow!("unterminated command substitution"))
+}
+
+// Reads the body of a backtick substitution starting just past the opening
+// backtick. Returns the inner command text and the index just past the
+// closing backtick.
+fn scan_backtick_substitution(chars: &[char], start: usize) -> Result<(String, usize)> {
+    for i in start..chars.len() {
+        if chars[i] == '`' {
+            return Ok((chars[start..i].iter().collect(), i + 1));
+        }
+    }
+    Err(anyhow!("unterminated command substitution"))
+}
+
+fn build_pipeline(tokens: Vec<Token>) -> Result<Vec<PipelineStage>> {
+    let mut stages: Vec<PipelineStage> = Vec::new();
+    let mut argv: Vec<CString> = Vec::new();
+    let mut redirs: Vec<Redirection> = Vec::new();
+    let mut iter = tokens.into_iter().peekable();
+
+    while let Some(tok) = iter.next() {
+        match tok {
+            Token::Word(w) => {
+                for expanded in expand_word(&w) {
+                    argv.push(CString::new(expanded).map_err(|_| anyhow!("NUL in arg"))?);
+                }
+            }
+            Token::Pipe => {
+                if argv.is_empty() { return Err(anyhow!("empty pipeline segment")); }
+                stages.push(PipelineStage { argv: std::mem::take(&mut argv), redirs: std::mem::take(&mut redirs) });
+            }
+            Token::RedirIn => {
+                let file = expect_redir_target(&mut iter, "input")?;
+                redirs.push(Redirection { fd: 0, target: RedirTarget::File(file), mode: RedirMode::Read });
+            }
+            Token::RedirOut(fd) => {
+                let file = expect_redir_target(&mut iter, "output")?;
+                redirs.push(Redirection { fd, target: RedirTarget::File(file), mode: RedirMode::Truncate });
+            }
+            Token::RedirAppend(fd) => {
+                let file = expect_redir_target(&mut iter, "output")?;
+                redirs.push(Redirection { fd, target: RedirTarget::File(file), mode: RedirMode::Append });
+            }
+            Token::FdDup(src, dst) => {
+                redirs.push(Redirection { fd: src, target: RedirTarget::Fd(dst), mode: RedirMode::Dup });
+            }
+        }
+    }
+    if argv.is_empty() { return Err(anyhow!("empty pipeline segment")); }
+    stages.push(PipelineStage { argv, redirs });
+    Ok(stages)
+}
+
+fn expect_redir_target(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>, what: &str) -> Result<String> {
+    match iter.next() {
+        Some(Token::Word(w)) => {
+            let mut expanded = expand_word(&w);
+            match expanded.len() {
+                1 => Ok(expanded.remove(0)),
+                _ => Err(anyhow!("ambiguous redirect target for {what}")),
+            }
+        }
+        _ => Err(anyhow!("missing {what} filename")),
+    }
+}
+
+// Expands a lexed word into one or more argv entries: `$VAR`/`${VAR}` are
+// substituted from the environment in every unquoted or double-quoted
+// segment, while single-quoted segments pass through untouched. If the word
+// had no quoting at all, a leading `~`/`~user` is then resolved to a home
+// directory and the result is glob-expanded against the filesystem.
+//
+// A `$(cmd)`/backtick substitution that makes up an entire unquoted word is
+// additionally word-split on whitespace, so `kill $(pgrep foo)` can expand
+// to several arguments the way it would in any other shell. A substitution
+// mixed with other text, or written inside double quotes, is spliced in as
+// a single piece instead.
+fn expand_word(word: &Word) -> Vec<String> {
+    if !word.quoted && word.segments.len() == 1 && let WordSegment::Substitution(cmd) = &word.segments[0] {
+        let captured = run_command_capture(cmd).unwrap_or_default();
+        return captured.split_whitespace().map(str::to_string).collect();
+    }
+    let mut combined = String::new();
+    for seg in &word.segments {
+        match seg {
+            WordSegment::Literal(s) => combined.push_str(s),
+            WordSegment::Plain(s) => combined.push_str(&expand_vars(s)),
+            WordSegment::Substitution(cmd) => combined.push_str(&run_command_capture(cmd).unwrap_or_default()),
+        }
+    }
+    if word.quoted { return vec![combined]; }
+    let combined = expand_tilde(&combined);
+    expand_glob(&combined)
+}
+
+// Runs `cmd` to completion with its stdout captured through a pipe instead of
+// the terminal, the way a backtick or `$(...)` substitution works in any
+// other shell: fork, point the child's fd 1 at the write end, drain the read
+// end in the parent while the child runs, then strip the trailing newlines
+// real shells also strip from captured output.
+fn run_command_capture(cmd: &str) -> Result<String> {
+    let (read_end, write_end) = pipe()?;
+    match unsafe { fork()? } {
+        ForkResult::Child => {
+            drop(read_end);
+            dup2(write_end.as_raw_fd(), 1).ok();
+            drop(write_end);
+            let mut jobs: Vec<Job> = Vec::new();
+            let mut next_job_id: usize = 1;
+            let code = match parse_line(cmd).and_then(|parsed| execute(parsed, &mut jobs, &mut next_job_id)) {
+                Ok(()) => 0,
+                Err(e) => { eprintln!("{e}"); 1 }
+            };
+            std::process::exit(code);
+        }
+        ForkResult::Parent { child } => {
+            drop(write_end);
+            let mut buf = Vec::new();
+            fs::File::from(read_end).read_to_end(&mut buf)?;
+            loop {
+                match waitpid(child, None) {
+                    Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => break,
+                    Ok(_) => continue,
+                    Err(e) => return Err(anyhow!("waitpid failed: {e}")),
+                }
+            }
+            let mut out = String::from_utf8_lossy(&buf).into_owned();
+            while out.ends_with('\n') { out.pop(); }
+            Ok(out)
+        }
+    }
+}
+
+fn expand_vars(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '$' && i + 1 < chars.len() {
+            if chars[i + 1] == '{' {
+                if let Some(end_rel) = chars[i + 2..].iter().position(|&c| c == '}') {
+                    let name: String = chars[i + 2..i + 2 + end_rel].iter().collect();
+                    out.push_str(&env::var(&name).unwrap_or_default());
+                    i = i + 2 + end_rel + 1;
+                    continue;
+                }
+            } else if chars[i + 1].is_alphabetic() || chars[i + 1] == '_' {
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') { end += 1; }
+                let name: String = chars[start..end].iter().collect();
+                out.push_str(&env::var(&name).unwrap_or_default());
+                i = end;
+                continue;
+            }
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+    out
+}
+
+fn expand_tilde(word: &str) -> String {
+    let Some(rest) = word.strip_prefix('~') else { return word.to_string(); };
+    if rest.is_empty() || rest.starts_with('/') {
+        if let Ok(home) = env::var("HOME") { return format!("{home}{rest}"); }
+        return word.to_string();
+    }
+    let (user, tail) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, ""),
+    };
+    match home_dir_of(user) {
+        Some(home) => format!("{home}{tail}"),
+        None => word.to_string(),
+    }
+}
+
+fn home_dir_of(user: &str) -> Option<String> {
+    nix::unistd::User::from_name(user).ok().flatten().map(|u| u.dir.display().to_string())
+}
+
+fn has_glob_chars(s: &str) -> bool {
+    s.chars().any(|c| matches!(c, '*' | '?' | '['))
+}
+
+// Translates `*`, `?`, and `[...]` into directory reads, matching one
+// slash-separated path component at a time. Returns the literal pattern
+// unchanged (as a single-element result) when it has no glob metacharacters
+// or when nothing on disk matches it.
+fn expand_glob(pattern: &str) -> Vec<String> {
+    if !has_glob_chars(pattern) { return vec![pattern.to_string()]; }
+
+    let (base, is_absolute) = match pattern.strip_prefix('/') {
+        Some(rest) => (rest, true),
+        None => (pattern, false),
+    };
+    let mut current: Vec<String> = vec![if is_absolute { "/".to_string() } else { ".".to_string() }];
+    for comp in base.split('/') {
+        if comp.is_empty() { continue; }
+        let mut next = Vec::new();
+        for dir in &current {
+            if has_glob_chars(comp) {
+                let Ok(read_dir) = fs::read_dir(dir) else { continue; };
+                let mut names: Vec<String> = read_dir
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .filter(|name| (comp.starts_with('.') || !name.starts_with('.')) && glob_match(comp, name))
+                    .collect();
+                names.sort();
+                next.extend(names.into_iter().map(|name| join_path(dir, &name)));
+            } else {
+                let candidate = join_path(dir, comp);
+                if fs::symlink_metadata(&candidate).is_ok() { next.push(candidate); }
+            }
+        }
+        current = next;
+        if current.is_empty() { break; }
+    }
+    if current.is_empty() { vec![pattern.to_string()] } else { current }
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    match dir {
+        "." => name.to_string(),
+        "/" => format!("/{name}"),
+        _ => format!("{dir}/{name}"),
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    glob_match_here(&p, &n)
+}
+
+fn glob_match_here(p: &[char], n: &[char]) -> bool {
+    match p.first() {
+        None => n.is_empty(),
+        Some('*') => (0..=n.len()).any(|k| glob_match_here(&p[1..], &n[k..])),
+        Some('?') => !n.is_empty() && glob_match_here(&p[1..], &n[1..]),
+        Some('[') => match p[1..].iter().position(|&c| c == ']') {
+            Some(end_rel) => {
+                let end = end_rel + 1;
+                if n.is_empty() { return false; }
+                let class = &p[1..end];
+                let (negate, class) = match class.first() {
+                    Some('!') | Some('^') => (true, &class[1..]),
+                    _ => (false, class),
+                };
+                let matched = glob_class_matches(class, n[0]) != negate;
+                matched && glob_match_here(&p[end + 1..], &n[1..])
+            }
+            None => !n.is_empty() && n[0] == '[' && glob_match_here(&p[1..], &n[1..]),
+        },
+        Some(&c) => !n.is_empty() && n[0] == c && glob_match_here(&p[1..], &n[1..]),
+    }
+}
+
+fn glob_class_matches(class: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            if c >= class[i] && c <= class[i + 2] { return true; }
+            i += 3;
+        } else {
+            if class[i] == c { return true; }
+            i += 1;
+        }
+    }
+    false
 }
 
 fn shell_split(s: &str) -> Vec<String> {